@@ -1,51 +1,217 @@
+use core::convert::TryFrom;
 use core::ops::{Deref, DerefMut};
 use x86_64::PhysAddr;
 
+/// Read-only access to a list of memory regions.
+///
+/// This is implemented both by [`MemoryMapOwned`], which stores its entries inline, and by
+/// [`MemoryMapRef`]/[`MemoryMapRefMut`], which borrow entries out of storage a bootloader already
+/// owns (e.g. a buffer handed to us by UEFI), so that code can be written generically over how
+/// the region array is backed.
+pub trait MemoryMap: Deref<Target = [MemoryRegion]> {
+    /// Produces a dense table of the `(start_addr, len)` ranges whose `region_type` matches
+    /// `filter`, with adjacent matching ranges merged. See
+    /// [`MemoryMapOwned::from_range_table`] for the reciprocal operation.
+    ///
+    /// Ranges are taken in the map's own order, and a range is merged only into the matching
+    /// range right before it in that order; abutting ranges that are apart in an unsorted map stay
+    /// separate entries.
+    ///
+    /// `Self` isn't necessarily capped at the same 32 entries as [`MemoryMapOwned`] (e.g. a
+    /// [`MemoryMapRefMut`] can wrap an arbitrarily large UEFI-provided descriptor buffer), so if
+    /// more matching, non-adjacent ranges turn up than `RangeTable` has room for, this returns
+    /// `RegionOverflowError` instead of panicking.
+    fn range_table(&self, filter: MemoryRegionType) -> Result<RangeTable, RegionOverflowError> {
+        let mut table = RangeTable::empty();
+        for region in self.iter() {
+            if region.len == 0 || region.region_type != filter {
+                continue;
+            }
+            match table.last_mut() {
+                Some(last) if last.start_addr + last.len == region.start_addr => {
+                    last.len += region.len;
+                }
+                _ => table.push(Range {
+                    start_addr: region.start_addr,
+                    len: region.len,
+                })?,
+            }
+        }
+        Ok(table)
+    }
+}
+
+impl<T: Deref<Target = [MemoryRegion]>> MemoryMap for T {}
+
+/// Mutable access to a list of memory regions, on top of the read-only [`MemoryMap`] trait.
+pub trait MemoryMapMut: MemoryMap + DerefMut<Target = [MemoryRegion]> {
+    /// Total number of region slots backing this map, used or not.
+    fn capacity(&self) -> usize;
+
+    /// Appends `region` as a new entry.
+    ///
+    /// # Panics
+    /// Panics if the map is already at `capacity()`.
+    fn add_region(&mut self, region: MemoryRegion);
+
+    /// Sorts the entries by `start_addr`, coalescing adjacent same-type entries and pushing
+    /// unused (zero-length) slots to the end. This can shrink the live entry count, so callers
+    /// must not cache indices or counts across a `sort()` call.
+    fn sort(&mut self);
+
+    /// Marks `region` as allocated, splitting the `Usable` entry that contains it so that the
+    /// requested range takes on `region.region_type` while the rest of the entry stays usable.
+    ///
+    /// `region` must lie entirely within a single existing `Usable` entry, otherwise this function
+    /// panics; a region spanning two abutting `Usable` entries is not split across them.
+    /// Splitting can grow the entry count by up to two, so if the map doesn't have enough free
+    /// slots left this returns `RegionOverflowError` instead of silently overflowing.
+    ///
+    /// Callers that add more than one region should call `sort` afterwards, as the split pieces
+    /// are appended to the end of the map rather than inserted in address order.
+    fn mark_allocated_region(&mut self, region: MemoryRegion) -> Result<(), RegionOverflowError>;
+
+    /// Reclassifies every `Usable` byte below [`LOW_MEMORY_END`] as `Reserved`, and the frame at
+    /// address zero as `FrameZero`, splitting entries as needed.
+    ///
+    /// May grow the entry count the same way `mark_allocated_region` can, returning
+    /// `RegionOverflowError` if the map runs out of slots.
+    fn guard_low_memory(&mut self) -> Result<(), RegionOverflowError>;
+}
+
+/// The end (exclusive) of the legacy low-memory range guarded by
+/// [`MemoryMapMut::guard_low_memory`].
+pub const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// A single `(start_addr, len)` range in a [`RangeTable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Range {
+    pub start_addr: PhysAddr,
+    pub len: u64,
+}
+
+/// A dense, fixed-capacity list of address ranges, as produced by [`MemoryMap::range_table`].
+///
+/// Unlike [`MemoryMapOwned`], a `RangeTable` carries no `region_type` per entry; every range in
+/// the table shares whatever `region_type` was passed as the `filter`.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct RangeTable {
+    entries: [Range; 32],
+    // u64 instead of usize so that the structure layout is platform independent
+    len: u64,
+}
+
+impl RangeTable {
+    fn empty() -> Self {
+        RangeTable {
+            entries: [Range { start_addr: PhysAddr::new(0), len: 0 }; 32],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, range: Range) -> Result<(), RegionOverflowError> {
+        if self.len as usize >= self.entries.len() {
+            return Err(RegionOverflowError);
+        }
+        self.entries[self.len as usize] = range;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+impl Deref for RangeTable {
+    type Target = [Range];
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries[0..self.len as usize]
+    }
+}
+
+impl DerefMut for RangeTable {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        let len = self.len as usize;
+        &mut self.entries[0..len]
+    }
+}
+
+/// Returned by [`MemoryMapMut::mark_allocated_region`] and [`MemoryMapMut::guard_low_memory`]
+/// when splitting an entry would require more entries than the map has free slots for, and by
+/// [`MemoryMap::range_table`] when the matching ranges don't fit in a [`RangeTable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionOverflowError;
+
+/// An owned, fixed-capacity memory map that stores its own 32 entries inline.
 #[derive(Debug)]
 #[repr(C)]
-pub struct MemoryMap {
+pub struct MemoryMapOwned {
     entries: [MemoryRegion; 32],
     // u64 instead of usize so that the structure layout is platform
     // independent
     next_entry_index: u64,
 }
 
-impl MemoryMap {
+impl MemoryMapOwned {
     pub fn new() -> Self {
-        MemoryMap {
+        MemoryMapOwned {
             entries: [MemoryRegion::empty(); 32],
             next_entry_index: 0,
         }
     }
 
-    pub fn add_region(&mut self, region: MemoryRegion) {
+    fn next_entry_index(&self) -> usize {
+        self.next_entry_index as usize
+    }
+
+    /// Rebuilds a map from a [`RangeTable`], tagging every range with `region_type`.
+    ///
+    /// This is the reciprocal of [`MemoryMap::range_table`].
+    pub fn from_range_table(table: &RangeTable, region_type: MemoryRegionType) -> Self {
+        let mut map = MemoryMapOwned::new();
+        for range in table.iter() {
+            map.add_region(MemoryRegion {
+                start_addr: range.start_addr,
+                len: range.len,
+                region_type,
+            });
+        }
+        map
+    }
+}
+
+impl MemoryMapMut for MemoryMapOwned {
+    fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn add_region(&mut self, region: MemoryRegion) {
         self.entries[self.next_entry_index()] = region;
         self.next_entry_index += 1;
     }
 
-    pub fn sort(&mut self) {
-        use core::cmp::Ordering;
+    fn sort(&mut self) {
+        let mut next_entry_index = self.next_entry_index();
+        sort_entries(&mut self.entries, &mut next_entry_index);
+        self.next_entry_index = next_entry_index as u64;
+    }
 
-        self.entries.sort_unstable_by(|r1, r2|
-            if r1.len == 0 {
-                Ordering::Greater
-            } else if r2.len == 0 {
-                Ordering::Less
-            } else {
-                r1.start_addr.cmp(&r2.start_addr)
-            }
-        );
-        if let Some(first_zero_index) = self.entries.iter().position(|r| r.len == 0) {
-            self.next_entry_index = first_zero_index as u64;
-        }
+    fn mark_allocated_region(&mut self, region: MemoryRegion) -> Result<(), RegionOverflowError> {
+        let mut next_entry_index = self.next_entry_index();
+        let result = mark_allocated_region_in(&mut self.entries, &mut next_entry_index, region);
+        self.next_entry_index = next_entry_index as u64;
+        result
     }
 
-    fn next_entry_index(&self) -> usize {
-        self.next_entry_index as usize
+    fn guard_low_memory(&mut self) -> Result<(), RegionOverflowError> {
+        let mut next_entry_index = self.next_entry_index();
+        let result = guard_low_memory_in(&mut self.entries, &mut next_entry_index);
+        self.next_entry_index = next_entry_index as u64;
+        result
     }
 }
 
-impl Deref for MemoryMap {
+impl Deref for MemoryMapOwned {
     type Target = [MemoryRegion];
 
     fn deref(&self) -> &Self::Target {
@@ -53,13 +219,270 @@ impl Deref for MemoryMap {
     }
 }
 
-impl DerefMut for MemoryMap {
+impl DerefMut for MemoryMapOwned {
     fn deref_mut(&mut self) -> &mut Self::Target {
         let next_index = self.next_entry_index();
         &mut self.entries[0..next_index]
     }
 }
 
+/// A memory map borrowed from storage a bootloader owns elsewhere, read-only.
+pub struct MemoryMapRef<'a> {
+    entries: &'a [MemoryRegion],
+}
+
+impl<'a> MemoryMapRef<'a> {
+    /// Wraps `entries`, which must contain only in-use regions (no trailing empty slots).
+    pub fn new(entries: &'a [MemoryRegion]) -> Self {
+        MemoryMapRef { entries }
+    }
+}
+
+impl<'a> Deref for MemoryMapRef<'a> {
+    type Target = [MemoryRegion];
+
+    fn deref(&self) -> &Self::Target {
+        self.entries
+    }
+}
+
+/// A memory map borrowed from storage a bootloader owns elsewhere, mutable.
+///
+/// `entries` is treated as the full capacity of the backing storage; unused trailing slots are
+/// represented the same way [`MemoryMapOwned`] represents them, as zero-length regions.
+pub struct MemoryMapRefMut<'a> {
+    entries: &'a mut [MemoryRegion],
+    next_entry_index: usize,
+}
+
+impl<'a> MemoryMapRefMut<'a> {
+    /// Wraps `entries` as the backing storage for a memory map, using `used` as the number of
+    /// leading entries that are already populated.
+    pub fn new(entries: &'a mut [MemoryRegion], used: usize) -> Self {
+        MemoryMapRefMut {
+            entries,
+            next_entry_index: used,
+        }
+    }
+}
+
+impl<'a> MemoryMapMut for MemoryMapRefMut<'a> {
+    fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn add_region(&mut self, region: MemoryRegion) {
+        self.entries[self.next_entry_index] = region;
+        self.next_entry_index += 1;
+    }
+
+    fn sort(&mut self) {
+        sort_entries(self.entries, &mut self.next_entry_index);
+    }
+
+    fn mark_allocated_region(&mut self, region: MemoryRegion) -> Result<(), RegionOverflowError> {
+        mark_allocated_region_in(self.entries, &mut self.next_entry_index, region)
+    }
+
+    fn guard_low_memory(&mut self) -> Result<(), RegionOverflowError> {
+        guard_low_memory_in(self.entries, &mut self.next_entry_index)
+    }
+}
+
+impl<'a> Deref for MemoryMapRefMut<'a> {
+    type Target = [MemoryRegion];
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries[0..self.next_entry_index]
+    }
+}
+
+impl<'a> DerefMut for MemoryMapRefMut<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries[0..self.next_entry_index]
+    }
+}
+
+fn sort_entries(entries: &mut [MemoryRegion], next_entry_index: &mut usize) {
+    use core::cmp::Ordering;
+
+    entries.sort_unstable_by(|r1, r2|
+        if r1.len == 0 {
+            Ordering::Greater
+        } else if r2.len == 0 {
+            Ordering::Less
+        } else {
+            r1.start_addr.cmp(&r2.start_addr)
+        }
+    );
+    if let Some(first_zero_index) = entries.iter().position(|r| r.len == 0) {
+        *next_entry_index = first_zero_index;
+    }
+
+    coalesce_entries(entries, next_entry_index);
+}
+
+/// Folds adjacent, abutting entries of the same `region_type` into one another, in a single
+/// left-to-right pass over the already address-sorted `entries[..*next_entry_index]`.
+///
+/// Merged-away slots are compacted out and zeroed, so `len == 0` entries still end up at the
+/// back of `entries` and `next_entry_index` shrinks to match.
+fn coalesce_entries(entries: &mut [MemoryRegion], next_entry_index: &mut usize) {
+    let mut write = 0;
+    for read in 0..*next_entry_index {
+        let candidate = entries[read];
+        if write > 0 {
+            let prev = entries[write - 1];
+            if prev.end_addr() == candidate.start_addr() && prev.region_type == candidate.region_type {
+                entries[write - 1].len += candidate.len;
+                continue;
+            }
+        }
+        entries[write] = candidate;
+        write += 1;
+    }
+    for entry in entries[write..*next_entry_index].iter_mut() {
+        *entry = MemoryRegion::empty();
+    }
+    *next_entry_index = write;
+}
+
+fn mark_allocated_region_in(
+    entries: &mut [MemoryRegion],
+    next_entry_index: &mut usize,
+    region: MemoryRegion,
+) -> Result<(), RegionOverflowError> {
+    for i in 0..*next_entry_index {
+        let entry = entries[i];
+        if entry.region_type != MemoryRegionType::Usable {
+            continue;
+        }
+        if region.start_addr() < entry.start_addr() || region.end_addr() > entry.end_addr() {
+            continue;
+        }
+
+        let before_is_empty = region.start_addr() == entry.start_addr();
+        let after_is_empty = region.end_addr() == entry.end_addr();
+        let new_entries = match (before_is_empty, after_is_empty) {
+            (true, true) => 0,
+            (true, false) | (false, true) => 1,
+            (false, false) => 2,
+        };
+        if new_entries > entries.len() - *next_entry_index {
+            return Err(RegionOverflowError);
+        }
+
+        if before_is_empty && after_is_empty {
+            // the allocation covers the whole entry
+            entries[i].region_type = region.region_type;
+        } else if before_is_empty {
+            // the allocation is at the start of the entry
+            let mut after = entry;
+            after.start_addr = region.end_addr();
+            after.len = entry.end_addr() - region.end_addr();
+            entries[i] = region;
+            entries[*next_entry_index] = after;
+            *next_entry_index += 1;
+        } else if after_is_empty {
+            // the allocation is at the end of the entry
+            let mut before = entry;
+            before.len = region.start_addr() - entry.start_addr();
+            entries[i] = before;
+            entries[*next_entry_index] = region;
+            *next_entry_index += 1;
+        } else {
+            // the allocation is in the middle of the entry, so it has to be split in three
+            let mut before = entry;
+            before.len = region.start_addr() - entry.start_addr();
+
+            let mut after = entry;
+            after.start_addr = region.end_addr();
+            after.len = entry.end_addr() - region.end_addr();
+
+            entries[i] = before;
+            entries[*next_entry_index] = after;
+            *next_entry_index += 1;
+            entries[*next_entry_index] = region;
+            *next_entry_index += 1;
+        }
+        return Ok(());
+    }
+
+    panic!(
+        "region {:?} is not contained in any usable region of the memory map",
+        region
+    );
+}
+
+/// Size in bytes of the single frame at address zero that gets its own [`MemoryRegionType`].
+const FRAME_ZERO_SIZE: u64 = 0x1000;
+
+fn guard_low_memory_in(
+    entries: &mut [MemoryRegion],
+    next_entry_index: &mut usize,
+) -> Result<(), RegionOverflowError> {
+    // Reclassify everything below `LOW_MEMORY_END` that's still usable.
+    for i in 0..*next_entry_index {
+        let entry = entries[i];
+        if entry.region_type != MemoryRegionType::Usable {
+            continue;
+        }
+        let start = entry.start_addr().as_u64();
+        if start >= LOW_MEMORY_END {
+            continue;
+        }
+
+        let guarded_end = core::cmp::min(entry.end_addr().as_u64(), LOW_MEMORY_END);
+        let guarded_len = guarded_end - start;
+        let needs_split = guarded_len < entry.len;
+        if needs_split && *next_entry_index >= entries.len() {
+            return Err(RegionOverflowError);
+        }
+
+        entries[i] = MemoryRegion {
+            start_addr: entry.start_addr,
+            len: guarded_len,
+            region_type: MemoryRegionType::Reserved,
+        };
+        if needs_split {
+            entries[*next_entry_index] = MemoryRegion {
+                start_addr: PhysAddr::new(guarded_end),
+                len: entry.len - guarded_len,
+                region_type: MemoryRegionType::Usable,
+            };
+            *next_entry_index += 1;
+        }
+    }
+
+    // Reclassify whatever now covers address zero as `FrameZero`.
+    for i in 0..*next_entry_index {
+        let entry = entries[i];
+        if entry.start_addr().as_u64() != 0 || entry.region_type == MemoryRegionType::FrameZero {
+            continue;
+        }
+
+        if entry.len > FRAME_ZERO_SIZE {
+            if *next_entry_index >= entries.len() {
+                return Err(RegionOverflowError);
+            }
+            entries[*next_entry_index] = MemoryRegion {
+                start_addr: PhysAddr::new(FRAME_ZERO_SIZE),
+                len: entry.len - FRAME_ZERO_SIZE,
+                region_type: entry.region_type,
+            };
+            *next_entry_index += 1;
+        }
+        entries[i] = MemoryRegion {
+            start_addr: entry.start_addr,
+            len: core::cmp::min(entry.len, FRAME_ZERO_SIZE),
+            region_type: MemoryRegionType::FrameZero,
+        };
+        break;
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct MemoryRegion {
@@ -122,24 +545,339 @@ pub struct E820MemoryRegion {
     pub acpi_extended_attributes: u32,
 }
 
-impl From<E820MemoryRegion> for MemoryRegion {
-    fn from(region: E820MemoryRegion) -> MemoryRegion {
-        let region_type = match region.region_type {
-            1 => MemoryRegionType::Usable,
-            2 => MemoryRegionType::Reserved,
-            3 => MemoryRegionType::AcpiReclaimable,
-            4 => MemoryRegionType::AcpiNvs,
-            5 => MemoryRegionType::BadMemory,
-            t => panic!("invalid region type {}", t),
+/// Returned by `TryFrom<E820MemoryRegion>` when the region's type byte isn't one of the values
+/// defined by the E820 spec, carrying the raw, unrecognized value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownRegionType(pub u32);
+
+impl TryFrom<E820MemoryRegion> for MemoryRegion {
+    type Error = UnknownRegionType;
+
+    /// Fallibly converts an E820 region, honoring the ACPI extended-attributes field.
+    ///
+    /// Firmware in the wild emits type values beyond the 1-5 defined by the E820 spec (e.g. type
+    /// 7 for persistent memory, or vendor-specific ranges), so unknown types are reported as
+    /// `UnknownRegionType` rather than guessed at. Regardless of the type byte, if bit 0 of
+    /// `acpi_extended_attributes` ("address range enabled") is clear, the region is treated as
+    /// `Reserved` rather than trusting the advertised type.
+    fn try_from(region: E820MemoryRegion) -> Result<MemoryRegion, UnknownRegionType> {
+        let range_enabled = region.acpi_extended_attributes & 0b1 != 0;
+
+        let region_type = if !range_enabled {
+            MemoryRegionType::Reserved
+        } else {
+            match region.region_type {
+                1 => MemoryRegionType::Usable,
+                2 => MemoryRegionType::Reserved,
+                3 => MemoryRegionType::AcpiReclaimable,
+                4 => MemoryRegionType::AcpiNvs,
+                5 => MemoryRegionType::BadMemory,
+                t => return Err(UnknownRegionType(t)),
+            }
         };
-        MemoryRegion {
+
+        Ok(MemoryRegion {
             start_addr: PhysAddr::new(region.start_addr),
             len: region.len,
-            region_type
-        }
+            region_type,
+        })
+    }
+}
+
+impl MemoryRegion {
+    /// Infallible fallback for [`TryFrom<E820MemoryRegion>`](MemoryRegion#impl-TryFrom<E820MemoryRegion>-for-MemoryRegion),
+    /// mapping unknown region types to `Reserved` instead of rejecting the conversion.
+    ///
+    /// This can't also be a `From` impl: the standard library's blanket
+    /// `impl<T, U> TryFrom<U> for T where U: Into<T>` would conflict with the `TryFrom` impl
+    /// above if `MemoryRegion` also implemented `From<E820MemoryRegion>` directly.
+    pub fn from_e820(region: E820MemoryRegion) -> MemoryRegion {
+        MemoryRegion::try_from(region).unwrap_or(MemoryRegion {
+            start_addr: PhysAddr::new(region.start_addr),
+            len: region.len,
+            region_type: MemoryRegionType::Reserved,
+        })
     }
 }
 
 extern "C" {
-    fn _improper_ctypes_check(_boot_info: MemoryMap);
+    fn _improper_ctypes_check(_boot_info: MemoryMapOwned);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usable(start: u64, len: u64) -> MemoryRegion {
+        MemoryRegion {
+            start_addr: PhysAddr::new(start),
+            len,
+            region_type: MemoryRegionType::Usable,
+        }
+    }
+
+    #[test]
+    fn coalesce_entries_merges_adjacent_same_type_and_leaves_others() {
+        let mut entries = [MemoryRegion::empty(); 32];
+        entries[0] = usable(0, 0x1000);
+        entries[1] = usable(0x1000, 0x1000);
+        entries[2] = MemoryRegion {
+            start_addr: PhysAddr::new(0x2000),
+            len: 0x1000,
+            region_type: MemoryRegionType::Reserved,
+        };
+        entries[3] = usable(0x3000, 0x1000);
+        let mut next_entry_index = 4;
+
+        coalesce_entries(&mut entries, &mut next_entry_index);
+
+        assert_eq!(next_entry_index, 3);
+        assert_eq!(entries[0], usable(0, 0x2000));
+        assert_eq!(
+            entries[1],
+            MemoryRegion {
+                start_addr: PhysAddr::new(0x2000),
+                len: 0x1000,
+                region_type: MemoryRegionType::Reserved,
+            }
+        );
+        assert_eq!(entries[2], usable(0x3000, 0x1000));
+        assert_eq!(entries[3], MemoryRegion::empty(), "the freed slot must be zeroed");
+    }
+
+    #[test]
+    fn mark_allocated_region_splits_at_the_leading_edge() {
+        let mut map = MemoryMapOwned::new();
+        map.add_region(usable(0, 0x3000));
+
+        map.mark_allocated_region(MemoryRegion {
+            start_addr: PhysAddr::new(0),
+            len: 0x1000,
+            region_type: MemoryRegionType::Kernel,
+        })
+        .unwrap();
+        map.sort();
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(
+            map[0],
+            MemoryRegion {
+                start_addr: PhysAddr::new(0),
+                len: 0x1000,
+                region_type: MemoryRegionType::Kernel,
+            }
+        );
+        assert_eq!(map[1], usable(0x1000, 0x2000));
+    }
+
+    #[test]
+    fn mark_allocated_region_splits_at_the_trailing_edge() {
+        let mut map = MemoryMapOwned::new();
+        map.add_region(usable(0, 0x3000));
+
+        map.mark_allocated_region(MemoryRegion {
+            start_addr: PhysAddr::new(0x2000),
+            len: 0x1000,
+            region_type: MemoryRegionType::Kernel,
+        })
+        .unwrap();
+        map.sort();
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[0], usable(0, 0x2000));
+        assert_eq!(
+            map[1],
+            MemoryRegion {
+                start_addr: PhysAddr::new(0x2000),
+                len: 0x1000,
+                region_type: MemoryRegionType::Kernel,
+            }
+        );
+    }
+
+    #[test]
+    fn mark_allocated_region_splits_three_ways_in_the_middle() {
+        let mut map = MemoryMapOwned::new();
+        map.add_region(usable(0, 0x3000));
+
+        map.mark_allocated_region(MemoryRegion {
+            start_addr: PhysAddr::new(0x1000),
+            len: 0x1000,
+            region_type: MemoryRegionType::Kernel,
+        })
+        .unwrap();
+        map.sort();
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[0], usable(0, 0x1000));
+        assert_eq!(
+            map[1],
+            MemoryRegion {
+                start_addr: PhysAddr::new(0x1000),
+                len: 0x1000,
+                region_type: MemoryRegionType::Kernel,
+            }
+        );
+        assert_eq!(map[2], usable(0x2000, 0x1000));
+    }
+
+    #[test]
+    fn mark_allocated_region_overflow_when_map_has_no_free_slots() {
+        let mut entries = [MemoryRegion::empty(); 32];
+        for (i, entry) in entries.iter_mut().enumerate().take(31) {
+            *entry = MemoryRegion {
+                start_addr: PhysAddr::new(LOW_MEMORY_END + i as u64 * 0x1000),
+                len: 0x1000,
+                region_type: MemoryRegionType::Reserved,
+            };
+        }
+        entries[31] = usable(0, 0x3000);
+        let mut map = MemoryMapRefMut::new(&mut entries, 32);
+
+        assert_eq!(
+            map.mark_allocated_region(MemoryRegion {
+                start_addr: PhysAddr::new(0x1000),
+                len: 0x1000,
+                region_type: MemoryRegionType::Kernel,
+            }),
+            Err(RegionOverflowError)
+        );
+    }
+
+    #[test]
+    fn try_from_e820_rejects_unknown_region_types() {
+        let region = E820MemoryRegion {
+            start_addr: 0,
+            len: 0x1000,
+            region_type: 7,
+            acpi_extended_attributes: 0b1,
+        };
+
+        assert_eq!(MemoryRegion::try_from(region), Err(UnknownRegionType(7)));
+    }
+
+    #[test]
+    fn try_from_e820_honors_the_address_range_enabled_bit() {
+        let region = E820MemoryRegion {
+            start_addr: 0,
+            len: 0x1000,
+            region_type: 1,
+            acpi_extended_attributes: 0b0,
+        };
+
+        assert_eq!(
+            MemoryRegion::try_from(region),
+            Ok(MemoryRegion {
+                start_addr: PhysAddr::new(0),
+                len: 0x1000,
+                region_type: MemoryRegionType::Reserved,
+            })
+        );
+    }
+
+    #[test]
+    fn guard_low_memory_splits_frame_zero_and_low_memory() {
+        let mut map = MemoryMapOwned::new();
+        map.add_region(usable(0, 0x20_0000));
+
+        map.guard_low_memory().unwrap();
+        map.sort();
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            map[0],
+            MemoryRegion {
+                start_addr: PhysAddr::new(0),
+                len: 0x1000,
+                region_type: MemoryRegionType::FrameZero,
+            }
+        );
+        assert_eq!(
+            map[1],
+            MemoryRegion {
+                start_addr: PhysAddr::new(0x1000),
+                len: LOW_MEMORY_END - 0x1000,
+                region_type: MemoryRegionType::Reserved,
+            }
+        );
+        assert_eq!(
+            map[2],
+            MemoryRegion {
+                start_addr: PhysAddr::new(LOW_MEMORY_END),
+                len: 0x20_0000 - LOW_MEMORY_END,
+                region_type: MemoryRegionType::Usable,
+            }
+        );
+    }
+
+    #[test]
+    fn guard_low_memory_overflow_leaves_the_overflowing_entry_intact() {
+        let mut entries = [MemoryRegion::empty(); 32];
+        // Fill every slot but one with unrelated, already-reserved regions so the low-memory
+        // split below has nowhere to put the leftover usable remainder.
+        for (i, entry) in entries.iter_mut().enumerate().take(31) {
+            *entry = MemoryRegion {
+                start_addr: PhysAddr::new(LOW_MEMORY_END + i as u64 * 0x1000),
+                len: 0x1000,
+                region_type: MemoryRegionType::Reserved,
+            };
+        }
+        entries[31] = usable(0, 0x20_0000);
+        let mut map = MemoryMapRefMut::new(&mut entries, 32);
+
+        let original = map[31];
+        assert_eq!(map.guard_low_memory(), Err(RegionOverflowError));
+        assert_eq!(
+            map[31], original,
+            "the usable entry must be left untouched when there's no free slot for the split"
+        );
+    }
+
+    #[test]
+    fn range_table_merges_adjacent_matching_regions() {
+        let mut map = MemoryMapOwned::new();
+        map.add_region(usable(0, 0x1000));
+        map.add_region(usable(0x1000, 0x1000));
+        map.add_region(MemoryRegion {
+            start_addr: PhysAddr::new(0x2000),
+            len: 0x1000,
+            region_type: MemoryRegionType::Reserved,
+        });
+        map.add_region(usable(0x3000, 0x1000));
+
+        let table = map.range_table(MemoryRegionType::Usable).unwrap();
+
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table[0],
+            Range {
+                start_addr: PhysAddr::new(0),
+                len: 0x2000,
+            }
+        );
+        assert_eq!(
+            table[1],
+            Range {
+                start_addr: PhysAddr::new(0x3000),
+                len: 0x1000,
+            }
+        );
+    }
+
+    #[test]
+    fn range_table_overflow_errors_instead_of_panicking() {
+        // A MemoryMapRefMut can wrap a buffer far larger than RangeTable's 32 slots (e.g. a
+        // UEFI descriptor array), so feed it more than 32 non-adjacent matching regions.
+        let mut entries = [MemoryRegion::empty(); 40];
+        for (i, entry) in entries.iter_mut().enumerate() {
+            *entry = MemoryRegion {
+                start_addr: PhysAddr::new(i as u64 * 0x2000),
+                len: 0x1000,
+                region_type: MemoryRegionType::Usable,
+            };
+        }
+        let map = MemoryMapRefMut::new(&mut entries, 40);
+
+        assert!(map.range_table(MemoryRegionType::Usable).is_err());
+    }
 }